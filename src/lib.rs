@@ -1,14 +1,139 @@
 
 
 use std::collections::HashMap;
-use std::sync::{RwLock, Mutex, Arc};
+use std::io::{self, Write};
+use std::sync::{RwLock, Mutex, Arc, OnceLock, mpsc};
 use lazy_static::lazy_static;
-use std::time::{ Instant, SystemTime };
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{ Instant, SystemTime, UNIX_EPOCH };
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::thread;
+
+// Escapes the characters InfluxDB line protocol treats as separators
+// (spaces and commas) in a measurement name.
+fn escape_influx_measurement(name: &str) -> String {
+    name.replace('\\', "\\\\").replace(' ', "\\ ").replace(',', "\\,")
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
+
+/// An owned, point-in-time copy of a single [`Rollup`]'s aggregates - safe to
+/// hold onto after the call that produced it, since no lock is retained.
+#[derive(Debug, Clone)]
+pub struct RollupSnapshot {
+    pub total: u32,
+    pub sample_count: u32,
+    pub min: u32,
+    pub max: u32,
+    pub start_time: SystemTime,
+    pub duration_secs: u64,
+    pub mean: f64,
+    histogram: Histogram,
+}
+
+impl RollupSnapshot {
+    /// The value at the `q`th percentile (0.0..=1.0, e.g. 0.99 for p99),
+    /// computed from the histogram copied out at snapshot time.
+    pub fn percentile(&self, q: f64) -> u32 {
+        self.histogram.percentile(q, self.sample_count)
+    }
+}
+
+/// An owned, point-in-time copy of a measure's rollups across every
+/// interval tier (e.g. 60s, hourly, daily), indexed the same way as
+/// [`RollupIntervals`]: `intervals[tier][rollup_index]`.
+#[derive(Debug, Clone)]
+pub struct MeasureSnapshot {
+    pub name: String,
+    pub intervals: Vec<Vec<RollupSnapshot>>,
+}
+
+// Significant-figure precision `p`: each bucket holds 2^(p+1) linear
+// sub-buckets, bounding relative error within a bucket to roughly 1/2^p.
+const HISTOGRAM_PRECISION: u32 = 7;
+
+// A logarithmically-bucketed histogram of u32 samples, HdrHistogram-style.
+// `counts` is allocated lazily on the first `record`, since most rollups in
+// a measure's 98-rollup interval tree never receive a sample.
+#[derive(Debug, Clone)]
+struct Histogram {
+    sub_bucket_count: usize,
+    counts: Vec<u32>,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram { sub_bucket_count: 1usize << (HISTOGRAM_PRECISION + 1), counts: Vec::new() }
+    }
+
+    fn bucket_count() -> usize {
+        (32 - HISTOGRAM_PRECISION) as usize
+    }
+
+    fn bucket_index(&self, value: u32) -> usize {
+        let highest_bit = if value == 0 { 0 } else { 31 - value.leading_zeros() };
+        if highest_bit <= HISTOGRAM_PRECISION {
+            0
+        } else {
+            (highest_bit - HISTOGRAM_PRECISION) as usize
+        }
+    }
+
+    fn index_of(&self, value: u32) -> usize {
+        let bucket = self.bucket_index(value);
+        let sub_bucket = ((value as usize) >> bucket) & (self.sub_bucket_count - 1);
+        bucket * self.sub_bucket_count + sub_bucket
+    }
+
+    fn value_for_index(&self, index: usize) -> u32 {
+        let bucket = index / self.sub_bucket_count;
+        let sub_bucket = (index % self.sub_bucket_count) as u32;
+        sub_bucket << bucket
+    }
+
+    fn reset(&mut self) {
+        for count in self.counts.iter_mut() {
+            *count = 0;
+        }
+    }
+
+    fn record(&mut self, value: u32) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; Self::bucket_count() * self.sub_bucket_count];
+        }
+        let index = self.index_of(value);
+        self.counts[index] += 1;
+    }
+
+    // Returns the lower bound of the bucket containing the `q`th percentile
+    // (0.0..=1.0), walking counts in ascending order until the running total
+    // reaches ceil(q * sample_count).
+    fn percentile(&self, q: f64, sample_count: u32) -> u32 {
+        if sample_count == 0 || self.counts.is_empty() {
+            return 0;
+        }
+        let target = (q * sample_count as f64).ceil() as u32;
+        let mut accumulated = 0u32;
+        for (index, count) in self.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            accumulated += count;
+            if accumulated >= target {
+                return self.value_for_index(index);
+            }
+        }
+        0
+    }
+}
 
 struct Rollup {
     pub total: u32,
     pub sample_count: u32,
+    pub min: u32,
+    pub max: u32,
+    histogram: Histogram,
     pub start_ticks: std::time::Instant,
     pub start_time: std::time::SystemTime,
     pub active: bool,
@@ -20,6 +145,9 @@ impl Rollup {
     fn reset_rollup(&mut self) {
         self.total = 0;
         self.sample_count = 0;
+        self.min = u32::MAX;
+        self.max = 0;
+        self.histogram.reset();
         self.start_ticks = Instant::now();
         self.start_time = SystemTime::now();
         self.first = false;
@@ -30,12 +158,18 @@ impl Rollup {
     fn add_value(&mut self, value: u32) {
         self.sample_count = self.sample_count + 1;
         self.total += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.histogram.record(value);
     }
 
     fn new() -> Rollup {
-        Rollup { 
+        Rollup {
             total: 0,
             sample_count: 0,
+            min: u32::MAX,
+            max: 0,
+            histogram: Histogram::new(),
             start_ticks: Instant::now(),
             start_time: SystemTime::now(),
             first: false,
@@ -44,8 +178,42 @@ impl Rollup {
         }
     }
 
+    fn mean(&self) -> f64 {
+        if self.sample_count == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.sample_count as f64
+        }
+    }
+
     fn debug_out_measures(&self) {
-        println!("[total: {}, sample_count: {}, start_ticks: {} seconds, start_time: {:?}, first: {}, active: {}, whole: {}", self.total, self.sample_count, self.start_ticks.elapsed().as_secs(), self.start_time, self.first, self.active, self.whole);
+        println!("[total: {}, sample_count: {}, min: {}, max: {}, start_ticks: {} seconds, start_time: {:?}, first: {}, active: {}, whole: {}", self.total, self.sample_count, self.min, self.max, self.start_ticks.elapsed().as_secs(), self.start_time, self.first, self.active, self.whole);
+    }
+
+    // Writes `measurement,interval=<tag> rollup_total=<total>i,rollup_count=<sample_count>i <timestamp_ns>`,
+    // or nothing if the rollup has no samples.
+    fn write_influx_line(&self, measurement: &str, interval_tag: &str, out: &mut dyn Write) -> io::Result<()> {
+        if self.sample_count == 0 {
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "{},interval={} rollup_total={}i,rollup_count={}i {}",
+            measurement, interval_tag, self.total, self.sample_count, unix_nanos(self.start_time)
+        )
+    }
+
+    fn snapshot(&self) -> RollupSnapshot {
+        RollupSnapshot {
+            total: self.total,
+            sample_count: self.sample_count,
+            min: self.min,
+            max: self.max,
+            start_time: self.start_time,
+            duration_secs: self.start_ticks.elapsed().as_secs(),
+            mean: self.mean(),
+            histogram: self.histogram.clone(),
+        }
     }
 
 }
@@ -97,16 +265,28 @@ impl Rollups {
         println!("current_rollup_index: {}, interval_seconds: {}", self.current_rollup_index, self.interval_seconds);
         for i in 0..self.rollups.len() {
             match self.rollups.get(i) {
-                Some(rollup) => 
-                    if rollup.sample_count > 0 
-                    { 
+                Some(rollup) =>
+                    if rollup.sample_count > 0
+                    {
                         println!("rollup: {}", i);
-                        rollup.debug_out_measures() 
+                        rollup.debug_out_measures()
                     },
                 None => panic!("no rollup at index {}", i)
             }
         }
     }
+
+    fn write_influx_lines(&self, measurement: &str, out: &mut dyn Write) -> io::Result<()> {
+        let interval_tag = format!("{}s", self.interval_seconds);
+        for rollup in &self.rollups {
+            rollup.write_influx_line(measurement, &interval_tag, out)?;
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Vec<RollupSnapshot> {
+        self.rollups.iter().map(Rollup::snapshot).collect()
+    }
 }
 
 struct RollupIntervals {
@@ -138,6 +318,17 @@ impl RollupIntervals {
             }
         }
     }
+
+    fn write_influx_lines(&self, measurement: &str, out: &mut dyn Write) -> io::Result<()> {
+        for rollups in &self.rollup_intervals {
+            rollups.write_influx_lines(measurement, out)?;
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Vec<Vec<RollupSnapshot>> {
+        self.rollup_intervals.iter().map(Rollups::snapshot).collect()
+    }
 }
 
 struct MeasureInner {
@@ -158,6 +349,15 @@ impl MeasureInner {
         println!("Measure Named: {} ", self.name);
         self.intervals.debug_out_measures();
     }
+
+    fn write_influx_lines(&self, out: &mut dyn Write) -> io::Result<()> {
+        let measurement = escape_influx_measurement(&self.name);
+        self.intervals.write_influx_lines(&measurement, out)
+    }
+
+    fn snapshot(&self) -> MeasureSnapshot {
+        MeasureSnapshot { name: self.name.clone(), intervals: self.intervals.snapshot() }
+    }
 }
 
 pub struct Measure {
@@ -176,50 +376,147 @@ impl Measure {
     fn debug_out_measures(&self) {
         self.inner.lock().unwrap().debug_out_measures();
     }
+
+    fn write_influx_lines(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.inner.lock().unwrap().write_influx_lines(out)
+    }
+
+    /// Takes only the per-measure lock, copies out the current rollups, and
+    /// releases it - the caller holds no lock on the returned snapshot.
+    fn snapshot(&self) -> MeasureSnapshot {
+        self.inner.lock().unwrap().snapshot()
+    }
 }
 
 unsafe impl Sync for Measure {}
 
 pub type MeasureHandle = usize;
-static NEXT_MEASURE_HANDLE: AtomicUsize = AtomicUsize::new(1);
-
 
-struct MeasuresInner {
-    measures: HashMap<MeasureHandle, Arc<Measure>>,
-    measures_by_name: HashMap<String, MeasureHandle>,
+// 32 buckets (bucket `i` holds 2^i slots) covers every index a `usize`
+// handle could reach long before memory would run out anyway.
+const MEASURE_STORE_BUCKETS: usize = 32;
+
+// A boxcar-style append-only store: once a bucket is allocated it's never
+// moved or freed, so `get` is a lock-free index into already-published
+// memory.
+type Bucket = Box<[OnceLock<Arc<Measure>>]>;
+
+struct MeasureStore {
+    buckets: [OnceLock<Bucket>; MEASURE_STORE_BUCKETS],
+    // Index reserved for the next push; bumped before that slot is published.
+    next_index: AtomicUsize,
+    // How far `0..len()` iteration may safely go; bumped only after `push`
+    // finishes writing the slot, so a reader never sees an index `get` can
+    // still return `None` for.
+    len: AtomicUsize,
 }
 
-impl MeasuresInner {
-    fn new() -> MeasuresInner {
-        MeasuresInner { measures: HashMap::new(), measures_by_name: HashMap::new() }
+impl MeasureStore {
+    fn new() -> MeasureStore {
+        MeasureStore {
+            buckets: std::array::from_fn(|_| OnceLock::new()),
+            next_index: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+        }
     }
 
-    fn new_measure(&mut self, name: &String) -> MeasureHandle {
-        if !self.measures_by_name.contains_key(name) {
-            let handle = NEXT_MEASURE_HANDLE.fetch_add(1, Ordering::SeqCst);
-            self.measures_by_name.insert(name.clone(), handle);
-            self.measures.insert(handle, Arc::new(Measure::new(name)));
-        }
-        match self.measures_by_name.get(name) {
-            Some(handle) => *handle,
-            None => panic!("Couldn't find measure when it should have been there: {}", name)
+    // `index` is 0-based. Returns (bucket, bucket_capacity, offset_in_bucket).
+    fn locate(index: usize) -> (usize, usize, usize) {
+        let bucket = (usize::BITS - 1 - (index + 1).leading_zeros()) as usize;
+        let bucket_capacity = 1usize << bucket;
+        let offset = (index + 1) - bucket_capacity;
+        (bucket, bucket_capacity, offset)
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    fn get(&self, index: usize) -> Option<Arc<Measure>> {
+        let (bucket, _, offset) = Self::locate(index);
+        self.buckets.get(bucket)?.get()?.get(offset)?.get().cloned()
+    }
+
+    fn push(&self, measure: Arc<Measure>) -> usize {
+        let index = self.next_index.fetch_add(1, Ordering::SeqCst);
+        let (bucket, bucket_capacity, offset) = Self::locate(index);
+        let slots = self.buckets[bucket].get_or_init(|| {
+            (0..bucket_capacity).map(|_| OnceLock::new()).collect::<Vec<_>>().into_boxed_slice()
+        });
+        slots[offset].set(measure).unwrap_or_else(|_| panic!("slot {} in bucket {} already published", offset, bucket));
+        // Publish the new length only now that the slot is actually readable.
+        self.len.store(index + 1, Ordering::Release);
+        index
+    }
+}
+
+// Messages accepted by the background aggregator thread spawned by
+// `Measures::spawn_aggregator`.
+enum AggregatorMessage {
+    Value(MeasureHandle, u32),
+    // Carries an ack channel so `AggregatorGuard::flush` can block until
+    // every message queued ahead of it has been applied.
+    Flush(mpsc::Sender<()>),
+    Shutdown,
+}
+
+pub struct Measures {
+    store: MeasureStore,
+    // Apart from the aggregator sender below, the name -> handle map is the
+    // only part of `Measures` behind a lock; looking a measure up by handle
+    // is lock-free, and recording a value takes only that measure's mutex.
+    names: RwLock<HashMap<String, MeasureHandle>>,
+    // Set while a background aggregator is running; `add_value`/
+    // `add_value_by_name` push through this instead of applying inline.
+    aggregator: RwLock<Option<mpsc::Sender<AggregatorMessage>>>,
+    // Mirrors `aggregator.is_some()`, checked before touching the `RwLock` so
+    // `add_value`'s synchronous path never takes that lock when no aggregator
+    // is running.
+    aggregator_active: AtomicBool,
+}
+
+impl Measures {
+    pub fn new() -> Measures {
+        Measures {
+            store: MeasureStore::new(),
+            names: RwLock::new(HashMap::new()),
+            aggregator: RwLock::new(None),
+            aggregator_active: AtomicBool::new(false),
         }
     }
 
-    fn exists(&self, name: &String) -> bool {
-        self.measures_by_name.contains_key(name)
+    fn by_handle(&self, handle: MeasureHandle) -> Option<Arc<Measure>> {
+        handle.checked_sub(1).and_then(|index| self.store.get(index))
     }
-    fn get_handle(&self, name: &String) -> MeasureHandle {
-        self.measures_by_name[name]
+
+    pub fn new_measure(&self, name: &String) -> MeasureHandle {
+        {
+            let names = self.names.read().unwrap();
+            if let Some(handle) = names.get(name) {
+                return *handle;
+            }
+        }
+
+        let mut names = self.names.write().unwrap();
+        if let Some(handle) = names.get(name) {
+            return *handle;
+        }
+        let index = self.store.push(Arc::new(Measure::new(name)));
+        let handle = index + 1;
+        names.insert(name.clone(), handle);
+        handle
     }
 
-    fn add_value(&mut self, handle: MeasureHandle, value: u32) {
-        match self.measures.get_mut(&handle) {
+    // Applies a value to a measure immediately, bypassing the aggregator
+    // queue. This is the synchronous hot path used when no aggregator is
+    // running, and the batch-application step used by the aggregator thread.
+    fn apply_value(&self, handle: MeasureHandle, value: u32) {
+        match self.by_handle(handle) {
             Some(measure) => measure.add_value(value),
             None => {
                 let unknown_measure_name = "measure_rollup::unknown_measure_handle_specified";
                 let unknown_measure_handle = self.new_measure(&unknown_measure_name.to_string());
-                match self.measures.get_mut(&unknown_measure_handle) {
+                match self.by_handle(unknown_measure_handle) {
                     Some(unknown_measure) => unknown_measure.add_value(1),
                     None => panic!("Measure named {} that was just created couldn't be found!", unknown_measure_name)
                 }
@@ -227,83 +524,183 @@ impl MeasuresInner {
         }
     }
 
-    fn add_value_by_name(&mut self, name: String, value: u32) {
-        let measure_handle;
-        {
-            match self.measures_by_name.get(&name) {
-                Some(handle) => measure_handle = *handle,
-                None => {
-                    let unknown_measure_name_name = "measure_rollup::unknown_measure_name_specified";
-                    let unknown_measure_name = self.new_measure(&unknown_measure_name_name.to_string());
-                    match self.measures.get_mut(&unknown_measure_name) {
-                        Some(unknown_measure) => unknown_measure.add_value(1),
-                        None => panic!("Measure named {} that was just created couldn't be found!", unknown_measure_name_name)
-                    }
-                    return;
+    pub fn add_value(&self, handle: MeasureHandle, value: u32) {
+        if !self.aggregator_active.load(Ordering::Acquire) {
+            self.apply_value(handle, value);
+            return;
+        }
+        let sender = self.aggregator.read().unwrap().clone();
+        match sender {
+            Some(sender) => { let _ = sender.send(AggregatorMessage::Value(handle, value)); }
+            None => self.apply_value(handle, value),
+        }
+    }
+
+    pub fn get_measure(&self, handle: MeasureHandle) -> Option<Arc<Measure>> {
+        self.by_handle(handle)
+    }
+
+    pub fn add_value_by_name(&self, name: String, value: u32) {
+        let measure_handle = {
+            let names = self.names.read().unwrap();
+            names.get(&name).copied()
+        };
+
+        match measure_handle {
+            Some(handle) => self.add_value(handle, value),
+            None => {
+                let unknown_measure_name_name = "measure_rollup::unknown_measure_name_specified";
+                let unknown_measure_handle = self.new_measure(&unknown_measure_name_name.to_string());
+                match self.by_handle(unknown_measure_handle) {
+                    Some(unknown_measure) => unknown_measure.add_value(1),
+                    None => panic!("Measure named {} that was just created couldn't be found!", unknown_measure_name_name)
                 }
             }
         }
+    }
+
+    /// Switches `add_value`/`add_value_by_name` into asynchronous mode,
+    /// applied by a dedicated background thread. Returns a guard that stops
+    /// the thread (and restores synchronous ingestion) when dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an aggregator is already running; drop the existing
+    /// `AggregatorGuard` first.
+    pub fn spawn_aggregator(&'static self) -> AggregatorGuard {
+        let (sender, receiver) = mpsc::channel::<AggregatorMessage>();
+        let already_running = {
+            let mut aggregator = self.aggregator.write().unwrap();
+            if aggregator.is_some() {
+                true
+            } else {
+                *aggregator = Some(sender.clone());
+                false
+            }
+        };
+        // Checked after releasing the lock, so a rejected call doesn't
+        // poison it for the aggregator that's already running.
+        if already_running {
+            panic!("spawn_aggregator called while an aggregator is already running");
+        }
+        self.aggregator_active.store(true, Ordering::Release);
+
+        let thread = thread::spawn(move || {
+            const BATCH_SIZE: usize = 256;
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            loop {
+                let first = match receiver.recv() {
+                    Ok(message) => message,
+                    Err(_) => break, // every sender (including our own clone) was dropped
+                };
+                batch.push(first);
+                while batch.len() < BATCH_SIZE {
+                    match receiver.try_recv() {
+                        Ok(message) => batch.push(message),
+                        Err(_) => break,
+                    }
+                }
+
+                let mut shutdown = false;
+                for message in batch.drain(..) {
+                    match message {
+                        AggregatorMessage::Value(handle, value) => self.apply_value(handle, value),
+                        AggregatorMessage::Flush(ack) => { let _ = ack.send(()); }
+                        AggregatorMessage::Shutdown => shutdown = true,
+                    }
+                }
+                if shutdown {
+                    break;
+                }
+            }
+        });
 
-        self.add_value(measure_handle, value);
+        AggregatorGuard { measures: self, sender, thread: Some(thread) }
     }
 
-    fn debug_out_measures(&self) {
+    pub fn debug_out_measures(&self) {
         println!("debug_out_measures called");
-        for (_, measure) in &self.measures {
-            measure.debug_out_measures();
+        for index in 0..self.store.len() {
+            if let Some(measure) = self.store.get(index) {
+                measure.debug_out_measures();
+            }
         }
     }
-}
 
-pub struct Measures {
-    inner: RwLock<MeasuresInner>,
-}
+    /// Returns an owned snapshot of every measure's current rollups. Only
+    /// takes the per-measure lock briefly while copying each one out, so no
+    /// lock is held by the time this returns.
+    pub fn snapshot(&self) -> Vec<MeasureSnapshot> {
+        (0..self.store.len())
+            .filter_map(|index| self.store.get(index))
+            .map(|measure| measure.snapshot())
+            .collect()
+    }
 
-impl Measures {
-    pub fn new() -> Measures {
-        Measures { inner: RwLock::new(MeasuresInner::new()) }
+    /// Snapshot of a single measure by handle, or `None` if the handle
+    /// doesn't refer to a known measure.
+    pub fn snapshot_one(&self, handle: MeasureHandle) -> Option<MeasureSnapshot> {
+        self.by_handle(handle).map(|measure| measure.snapshot())
     }
-    
-    pub fn new_measure(&self, name: &String) -> MeasureHandle {
-        let exists;
-        {
-            let measures_inner = self.inner.read().unwrap();
-            exists = measures_inner.exists(name);
-        }
 
-        if !exists {
-            let mut measures_inner = self.inner.write().unwrap();
-            measures_inner.new_measure(name)
-        }
-        else {
-            let measures_inner = self.inner.read().unwrap();
-            measures_inner.get_handle(name)
-        }
+    /// Snapshot of a single measure by name, or `None` if no measure with
+    /// that name has been created yet.
+    pub fn snapshot_by_name(&self, name: &String) -> Option<MeasureSnapshot> {
+        let handle = *self.names.read().unwrap().get(name)?;
+        self.snapshot_one(handle)
     }
 
-    pub fn add_value(&self, handle: MeasureHandle, value: u32) {
-        let write = self.inner.write();
-        let mut measures_inner = write.unwrap();
-        measures_inner.add_value(handle, value);
+    /// Serializes every measure's non-empty rollups into InfluxDB line
+    /// protocol, one line per rollup interval with samples.
+    pub fn export_influx_lines(&self) -> String {
+        let mut buffer = Vec::new();
+        self.write_influx_lines(&mut buffer).expect("writing to a Vec<u8> cannot fail");
+        String::from_utf8(buffer).expect("influx line protocol output is always valid UTF-8")
     }
 
-    pub fn get_measure(&self, handle: MeasureHandle) -> Option<Arc<Measure>> {
-        let measures_inner = self.inner.write().unwrap();
-        match measures_inner.measures.get(&handle) {
-            Some(result) => Some(result.clone()),
-            None => Option::None
+    /// Same as [`Measures::export_influx_lines`], but writes directly to any
+    /// `io::Write` (a file, socket, etc.) instead of building a `String`.
+    pub fn write_influx_lines(&self, out: &mut dyn Write) -> io::Result<()> {
+        for index in 0..self.store.len() {
+            if let Some(measure) = self.store.get(index) {
+                measure.write_influx_lines(out)?;
+            }
         }
-    }
- 
-    pub fn add_value_by_name(&self, name: String, value: u32) {
-        let mut measures_inner = self.inner.write().unwrap();
-        measures_inner.add_value_by_name(name, value);
+        Ok(())
     }
 
-    pub fn debug_out_measures(&self) {
-        self.inner.read().unwrap().debug_out_measures();
+}
+
+/// Owns the background aggregator thread started by
+/// [`Measures::spawn_aggregator`]. Dropping it restores synchronous
+/// ingestion and joins the thread.
+pub struct AggregatorGuard {
+    measures: &'static Measures,
+    sender: mpsc::Sender<AggregatorMessage>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AggregatorGuard {
+    /// Blocks until every value queued ahead of this call has been applied.
+    pub fn flush(&self) {
+        let (ack_sender, ack_receiver) = mpsc::channel();
+        if self.sender.send(AggregatorMessage::Flush(ack_sender)).is_ok() {
+            let _ = ack_receiver.recv();
+        }
     }
+}
 
+impl Drop for AggregatorGuard {
+    fn drop(&mut self) {
+        // Safe to clear unconditionally: `spawn_aggregator` panics on
+        // re-entry, so this guard is always the sole owner of `aggregator`.
+        self.measures.aggregator_active.store(false, Ordering::Release);
+        *self.measures.aggregator.write().unwrap() = None;
+        let _ = self.sender.send(AggregatorMessage::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
 }
 
 lazy_static! {
@@ -323,17 +720,49 @@ mod tests {
         let mut rollup: Rollup = Rollup{
             total: 5,
             sample_count: 5,
+            min: 1,
+            max: 4,
+            histogram: Histogram::new(),
             start_ticks: std::time::Instant::now(),
             start_time: std::time::SystemTime::now(),
             active: true,
             first: true,
-            whole: true,            
+            whole: true,
         };
         assert_eq!(rollup.total, 5);
         rollup.reset_rollup();
         assert_eq!(rollup.total, 0);
     }
 
+    #[test]
+    fn test_histogram_percentile() {
+        let mut rollup = Rollup::new();
+        for value in 1..=100u32 {
+            rollup.add_value(value);
+        }
+        assert_eq!(rollup.min, 1);
+        assert_eq!(rollup.max, 100);
+        let p99 = rollup.snapshot().percentile(0.99);
+        assert!((95..=100).contains(&p99), "p99 was {}", p99);
+    }
+
+    #[test]
+    fn test_histogram_percentile_accuracy_at_realistic_magnitude() {
+        let mut rollup = Rollup::new();
+        for value in 1..=5000u32 {
+            rollup.add_value(value);
+        }
+        let snapshot = rollup.snapshot();
+
+        let p50 = snapshot.percentile(0.50);
+        let relative_error = (p50 as f64 - 2500.0).abs() / 2500.0;
+        assert!(relative_error < 0.05, "p50 was {} ({:.1}% off exact median 2500)", p50, relative_error * 100.0);
+
+        let p99 = snapshot.percentile(0.99);
+        let relative_error = (p99 as f64 - 4950.0).abs() / 4950.0;
+        assert!(relative_error < 0.05, "p99 was {} ({:.1}% off exact p99 4950)", p99, relative_error * 100.0);
+    }
+
     #[test]
     fn test_rollups() {
         let mut rollups = RollupIntervals::new();
@@ -431,4 +860,75 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_export_influx_lines() {
+        // Exercise the Measure-level exporter directly so this test doesn't
+        // churn the process-wide MeasureHandle counter that test_multi_thread
+        // asserts an exact value against.
+        let measure = Measure::new(&"Water Meter 1".to_string());
+        measure.add_value(45);
+
+        let mut buffer = Vec::new();
+        measure.write_influx_lines(&mut buffer).unwrap();
+        let lines = String::from_utf8(buffer).unwrap();
+
+        assert!(lines.contains("Water\\ Meter\\ 1,interval=60s rollup_total=45i,rollup_count=1i "));
+    }
+
+    #[test]
+    fn test_export_influx_lines_skips_empty_rollups() {
+        let measure = Measure::new(&"Unused Meter".to_string());
+
+        let mut buffer = Vec::new();
+        measure.write_influx_lines(&mut buffer).unwrap();
+
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_aggregator_applies_queued_values() {
+        let measures: &'static Measures = Box::leak(Box::new(Measures::new()));
+        let handle = measures.new_measure(&"Queued Meter".to_string());
+
+        let aggregator = measures.spawn_aggregator();
+        for _ in 0..10 {
+            measures.add_value(handle, 5);
+        }
+        aggregator.flush();
+
+        let measure = measures.get_measure(handle).unwrap();
+        let mut buffer = Vec::new();
+        measure.write_influx_lines(&mut buffer).unwrap();
+        assert!(String::from_utf8(buffer).unwrap().contains("rollup_total=50i,rollup_count=10i"));
+    }
+
+    #[test]
+    #[should_panic(expected = "already running")]
+    fn test_spawn_aggregator_rejects_reentry() {
+        let measures: &'static Measures = Box::leak(Box::new(Measures::new()));
+        let _first = measures.spawn_aggregator();
+        let _second = measures.spawn_aggregator();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let measures = Measures::new();
+        let handle = measures.new_measure(&"Snapshot Meter".to_string());
+        measures.add_value(handle, 10);
+        measures.add_value(handle, 20);
+
+        let one = measures.snapshot_one(handle).unwrap();
+        assert_eq!(one.name, "Snapshot Meter");
+        let current = &one.intervals[0][0];
+        assert_eq!(current.total, 30);
+        assert_eq!(current.sample_count, 2);
+        assert_eq!(current.mean, 15.0);
+
+        let by_name = measures.snapshot_by_name(&"Snapshot Meter".to_string()).unwrap();
+        assert_eq!(by_name.intervals[0][0].total, 30);
+
+        assert!(measures.snapshot_by_name(&"No Such Meter".to_string()).is_none());
+        assert_eq!(measures.snapshot().len(), 1);
+    }
+
 }